@@ -0,0 +1,256 @@
+//! Pool configuration.
+
+use std::default::Default;
+use std::fmt;
+use std::time::Duration;
+
+use CustomizeConnection;
+
+/// A builder for `Config`.
+pub struct ConfigBuilder<C, E> {
+    config: Config<C, E>,
+}
+
+impl<C, E> fmt::Debug for ConfigBuilder<C, E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "ConfigBuilder {{ config: {:?} }}", self.config)
+    }
+}
+
+impl<C, E> ConfigBuilder<C, E> {
+    /// Constructs a new `ConfigBuilder` with default settings.
+    pub fn new() -> ConfigBuilder<C, E> {
+        ConfigBuilder { config: Config::default() }
+    }
+
+    /// Sets the maximum number of connections managed by the pool.
+    ///
+    /// Must be greater than zero.
+    ///
+    /// Defaults to 10.
+    pub fn max_size(mut self, max_size: u32) -> ConfigBuilder<C, E> {
+        self.config.max_size = max_size;
+        self
+    }
+
+    /// Sets the number of connections the pool opens at startup and keeps
+    /// open when it is not under load.
+    ///
+    /// This counts every connection the pool manages, idle or checked out.
+    /// The pool will opportunistically grow past this toward `max_size`
+    /// under load, and shrink back down to it once the load subsides.
+    /// `None` (the default) keeps `max_size` connections open at all times,
+    /// matching the old fixed-size behavior.
+    pub fn min_idle(mut self, min_idle: Option<u32>) -> ConfigBuilder<C, E> {
+        self.config.min_idle = min_idle;
+        self
+    }
+
+    /// Sets the number of threads used to run maintenance tasks, such as
+    /// connection creation, in the background.
+    ///
+    /// Defaults to 3.
+    pub fn helper_threads(mut self, helper_threads: u32) -> ConfigBuilder<C, E> {
+        self.config.helper_threads = helper_threads;
+        self
+    }
+
+    /// If true, `ConnectionManager::is_valid` will be called on a connection
+    /// before it is returned from `Pool::get`.
+    ///
+    /// Defaults to true.
+    pub fn test_on_check_out(mut self, test_on_check_out: bool) -> ConfigBuilder<C, E> {
+        self.config.test_on_check_out = test_on_check_out;
+        self
+    }
+
+    /// If true, `ConnectionManager::is_valid` will be run in the background
+    /// on idle connections, evicting any that fail the check. Evicted
+    /// connections are replaced only while the pool holds fewer than
+    /// `min_idle` connections.
+    ///
+    /// This avoids paying the cost of `is_valid` on the hot `Pool::get` path,
+    /// at the expense of a connection only being known-bad once the next
+    /// background sweep runs. Can be combined with `test_on_check_out`.
+    ///
+    /// Defaults to false.
+    pub fn test_while_idle(mut self, test_while_idle: bool) -> ConfigBuilder<C, E> {
+        self.config.test_while_idle = test_while_idle;
+        self
+    }
+
+    /// If true, `Pool::new` will synchronously wait for the pool's initial
+    /// `min_idle` connections (`max_size` if `min_idle` is `None`) to be
+    /// established, returning an error if it is unable to do so within
+    /// `connection_timeout`.
+    ///
+    /// Defaults to true.
+    pub fn initialization_fail_fast(mut self,
+                                     initialization_fail_fast: bool)
+                                     -> ConfigBuilder<C, E> {
+        self.config.initialization_fail_fast = initialization_fail_fast;
+        self
+    }
+
+    /// Sets the maximum amount of time that `Pool::get` will wait for a
+    /// connection before returning an error.
+    ///
+    /// Defaults to 30 seconds.
+    pub fn connection_timeout(mut self, connection_timeout: Duration) -> ConfigBuilder<C, E> {
+        self.config.connection_timeout = connection_timeout;
+        self
+    }
+
+    /// Sets the maximum amount of time that a connection may sit idle in the
+    /// pool before it is closed.
+    ///
+    /// `None` disables the idle timeout. Defaults to 10 minutes.
+    pub fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> ConfigBuilder<C, E> {
+        self.config.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Sets the maximum lifetime of a connection, regardless of how
+    /// frequently it is used.
+    ///
+    /// `None` disables the lifetime limit. Defaults to 30 minutes.
+    pub fn max_lifetime(mut self, max_lifetime: Option<Duration>) -> ConfigBuilder<C, E> {
+        self.config.max_lifetime = max_lifetime;
+        self
+    }
+
+    /// Sets a `CustomizeConnection` used to set up a connection each time it
+    /// is checked out of the pool, and tear it down each time it is returned.
+    ///
+    /// Defaults to `None`.
+    pub fn connection_customizer(mut self,
+                                  connection_customizer: Box<CustomizeConnection<C, E>>)
+                                  -> ConfigBuilder<C, E> {
+        self.config.connection_customizer = Some(connection_customizer);
+        self
+    }
+
+    /// Consumes the `ConfigBuilder`, returning the `Config` it represents.
+    pub fn build(self) -> Config<C, E> {
+        self.config
+    }
+}
+
+/// Configuration for a `Pool`.
+pub struct Config<C, E> {
+    max_size: u32,
+    min_idle: Option<u32>,
+    helper_threads: u32,
+    test_on_check_out: bool,
+    test_while_idle: bool,
+    initialization_fail_fast: bool,
+    connection_timeout: Duration,
+    idle_timeout: Option<Duration>,
+    max_lifetime: Option<Duration>,
+    connection_customizer: Option<Box<CustomizeConnection<C, E>>>,
+}
+
+impl<C, E> fmt::Debug for Config<C, E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "Config {{ max_size: {:?}, min_idle: {:?}, helper_threads: {:?}, \
+                test_on_check_out: {:?}, test_while_idle: {:?}, \
+                initialization_fail_fast: {:?}, connection_timeout: {:?}, \
+                idle_timeout: {:?}, max_lifetime: {:?}, connection_customizer: {} }}",
+               self.max_size,
+               self.min_idle,
+               self.helper_threads,
+               self.test_on_check_out,
+               self.test_while_idle,
+               self.initialization_fail_fast,
+               self.connection_timeout,
+               self.idle_timeout,
+               self.max_lifetime,
+               if self.connection_customizer.is_some() { "Some(..)" } else { "None" })
+    }
+}
+
+impl<C, E> Default for Config<C, E> {
+    fn default() -> Config<C, E> {
+        Config {
+            max_size: 10,
+            min_idle: None,
+            helper_threads: 3,
+            test_on_check_out: true,
+            test_while_idle: false,
+            initialization_fail_fast: true,
+            connection_timeout: Duration::seconds(30),
+            idle_timeout: Some(Duration::minutes(10)),
+            max_lifetime: Some(Duration::minutes(30)),
+            connection_customizer: None,
+        }
+    }
+}
+
+impl<C, E> Config<C, E> {
+    /// Creates a `ConfigBuilder` initialized with default settings.
+    pub fn builder() -> ConfigBuilder<C, E> {
+        ConfigBuilder::new()
+    }
+
+    /// The maximum number of connections managed by the pool.
+    pub fn max_size(&self) -> u32 {
+        self.max_size
+    }
+
+    /// The number of connections, idle or checked out, that the pool opens
+    /// at startup and keeps open when it is not under load.
+    ///
+    /// A return value of `None` means the pool holds `max_size` connections
+    /// open at all times.
+    pub fn min_idle(&self) -> Option<u32> {
+        self.min_idle
+    }
+
+    /// The number of threads used to run maintenance tasks in the
+    /// background.
+    pub fn helper_threads(&self) -> u32 {
+        self.helper_threads
+    }
+
+    /// Whether connections are validated before being checked out of the
+    /// pool.
+    pub fn test_on_check_out(&self) -> bool {
+        self.test_on_check_out
+    }
+
+    /// Whether idle connections are validated in the background.
+    pub fn test_while_idle(&self) -> bool {
+        self.test_while_idle
+    }
+
+    /// Whether `Pool::new` waits for the pool's initial connections to be
+    /// established before returning.
+    pub fn initialization_fail_fast(&self) -> bool {
+        self.initialization_fail_fast
+    }
+
+    /// The maximum amount of time that `Pool::get` will wait for a
+    /// connection before returning an error.
+    pub fn connection_timeout(&self) -> Duration {
+        self.connection_timeout
+    }
+
+    /// The maximum amount of time that a connection may sit idle in the
+    /// pool before it is closed.
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// The maximum lifetime of a connection, regardless of how frequently
+    /// it is used.
+    pub fn max_lifetime(&self) -> Option<Duration> {
+        self.max_lifetime
+    }
+
+    /// The `CustomizeConnection` used to set up and tear down checked-out
+    /// connections, if one is configured.
+    pub fn connection_customizer(&self) -> Option<&CustomizeConnection<C, E>> {
+        self.connection_customizer.as_ref().map(|c| &**c)
+    }
+}
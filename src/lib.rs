@@ -7,6 +7,7 @@
 extern crate log;
 extern crate time;
 
+use std::cmp;
 use std::collections::RingBuf;
 use std::error::Error;
 use std::fmt;
@@ -73,13 +74,38 @@ impl<E> ErrorHandler<E> for LoggingErrorHandler where E: fmt::Debug {
     }
 }
 
+/// A trait which allows for customization of connections as they are
+/// checked out of, or returned to, the pool.
+///
+/// This gives users a place to run per-checkout session setup (for example,
+/// `SET statement_timeout` or resetting session variables) without baking
+/// it into every `ConnectionManager`.
+pub trait CustomizeConnection<C, E>: Send+Sync {
+    /// Called after a connection is checked out of the pool, once any
+    /// `ConnectionManager::is_valid` check has passed.
+    ///
+    /// If this returns an `Err`, the connection is discarded and replaced,
+    /// and the error is routed through the pool's `ErrorHandler`, exactly
+    /// as a failed `is_valid` check is handled.
+    fn on_acquire(&self, conn: &mut C) -> Result<(), E>;
+
+    /// Called just before a connection is returned to the pool.
+    fn on_release(&self, conn: &mut C);
+}
+
+struct IdleConn<C> {
+    conn: C,
+    created_at: SteadyTime,
+    last_used_at: SteadyTime,
+}
+
 struct PoolInternals<C> {
-    conns: RingBuf<C>,
+    conns: RingBuf<IdleConn<C>>,
     num_conns: u32,
 }
 
 struct SharedPool<M> where M: ConnectionManager {
-    config: Config,
+    config: Config<<M as ConnectionManager>::Connection, <M as ConnectionManager>::Error>,
     manager: M,
     error_handler: Box<ErrorHandler<<M as ConnectionManager>::Error>>,
     internals: Mutex<PoolInternals<<M as ConnectionManager>::Connection>>,
@@ -88,24 +114,128 @@ struct SharedPool<M> where M: ConnectionManager {
 }
 
 fn add_connection<M>(delay: Duration, shared: &Arc<SharedPool<M>>) where M: ConnectionManager {
+    connect(delay, shared, false);
+}
+
+/// Schedules a connection attempt for a slot whose `num_conns` count has
+/// already been bumped by the caller under the `internals` lock.
+///
+/// This is used by `Pool::get` to grow the pool under load: the slot must be
+/// reserved at the moment the decision to grow is made (not once `connect`
+/// finally completes), or concurrent callers racing the same check could
+/// schedule far more connections than `Config::max_size` allows.
+fn add_reserved_connection<M>(delay: Duration, shared: &Arc<SharedPool<M>>)
+    where M: ConnectionManager
+{
+    connect(delay, shared, true);
+}
+
+fn connect<M>(delay: Duration, shared: &Arc<SharedPool<M>>, reserved: bool)
+    where M: ConnectionManager
+{
     let new_shared = shared.clone();
     shared.thread_pool.run_after(delay, move || {
         let shared = new_shared;
         match shared.manager.connect() {
             Ok(conn) => {
+                let now = SteadyTime::now();
                 let mut internals = shared.internals.lock().unwrap();
-                internals.conns.push_back(conn);
-                internals.num_conns += 1;
+                internals.conns.push_back(IdleConn {
+                    conn: conn,
+                    created_at: now,
+                    last_used_at: now,
+                });
+                if !reserved {
+                    internals.num_conns += 1;
+                }
                 shared.cond.notify_one();
             }
             Err(err) => {
                 shared.error_handler.handle_error(err);
-                add_connection(Duration::seconds(1), &shared);
+                connect(Duration::seconds(1), &shared, reserved);
             },
         }
     });
 }
 
+/// Schedules the recurring background sweep over idle connections, which
+/// evicts connections that have been idle for longer than
+/// `Config::idle_timeout`, alive for longer than `Config::max_lifetime`, or
+/// (if `Config::test_while_idle` is set) that fail `ConnectionManager::is_valid`.
+/// It then closes idle connections while the pool holds more than
+/// `Config::min_idle` connections, and opens new ones while it holds fewer.
+fn schedule_reaper<M>(shared: &Arc<SharedPool<M>>) where M: ConnectionManager {
+    let interval = match (shared.config.idle_timeout(), shared.config.max_lifetime()) {
+        (Some(idle_timeout), Some(max_lifetime)) => cmp::min(idle_timeout, max_lifetime),
+        (Some(idle_timeout), None) => idle_timeout,
+        (None, Some(max_lifetime)) => max_lifetime,
+        (None, None) if shared.config.test_while_idle() ||
+                        shared.config.min_idle().is_some() => Duration::seconds(30),
+        (None, None) => return,
+    };
+
+    let shared = shared.clone();
+    shared.thread_pool.run_at_fixed_rate(interval, interval, move || reap_connections(&shared));
+}
+
+fn reap_connections<M>(shared: &Arc<SharedPool<M>>) where M: ConnectionManager {
+    let idle_timeout = shared.config.idle_timeout();
+    let max_lifetime = shared.config.max_lifetime();
+    let test_while_idle = shared.config.test_while_idle();
+    let min_idle = shared.config.min_idle().unwrap_or(shared.config.max_size());
+
+    let num_idle = shared.internals.lock().unwrap().conns.len();
+
+    // Pop one idle connection at a time, releasing the lock around the
+    // (potentially blocking) `is_valid` check so a concurrent `get` or
+    // `put_back` is never stalled by a slow validation.
+    for _ in 0..num_idle {
+        let mut conn = match shared.internals.lock().unwrap().conns.pop_front() {
+            Some(conn) => conn,
+            None => break,
+        };
+
+        let now = SteadyTime::now();
+        let mut reap = idle_timeout.map_or(false, |t| now - conn.last_used_at > t) ||
+            max_lifetime.map_or(false, |t| now - conn.created_at > t);
+
+        if !reap && test_while_idle {
+            if let Err(e) = shared.manager.is_valid(&mut conn.conn) {
+                shared.error_handler.handle_error(e);
+                reap = true;
+            }
+        }
+
+        let mut internals = shared.internals.lock().unwrap();
+        if reap {
+            internals.num_conns -= 1;
+        } else {
+            internals.conns.push_back(conn);
+        }
+    }
+
+    let mut internals = shared.internals.lock().unwrap();
+
+    // Close idle connections while the pool holds more than `min_idle`
+    // connections in total, letting it shrink back down once a burst of
+    // traffic subsides.
+    while internals.num_conns > min_idle && !internals.conns.is_empty() {
+        internals.conns.pop_front();
+        internals.num_conns -= 1;
+    }
+
+    // Reserve the refill slots here, under the same lock that computed
+    // `to_add`, so a concurrent burst of `Pool::get` growth can't race
+    // these un-started connects and overshoot `max_size` once they land.
+    let to_add = min_idle.saturating_sub(internals.num_conns);
+    internals.num_conns += to_add;
+    drop(internals);
+
+    for _ in 0..to_add {
+        add_reserved_connection(Duration::zero(), shared);
+    }
+}
+
 /// A generic connection pool.
 pub struct Pool<M> where M: ConnectionManager {
     shared: Arc<SharedPool<M>>,
@@ -143,6 +273,53 @@ impl Error for InitializationError {
     }
 }
 
+/// An error returned by `Pool::add` if the connection could not be added to
+/// the pool.
+///
+/// In either case, the connection passed to `Pool::add` is handed back so
+/// that it is not silently dropped.
+pub enum AddError<C> {
+    /// The connection failed the `ConnectionManager`'s `has_broken` check.
+    Broken(C),
+    /// The pool was already at `Config::max_size` capacity.
+    PoolFull(C),
+}
+
+impl<C> fmt::Debug for AddError<C> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AddError::Broken(_) => fmt.write_str("AddError::Broken(..)"),
+            AddError::PoolFull(_) => fmt.write_str("AddError::PoolFull(..)"),
+        }
+    }
+}
+
+impl<C> fmt::Display for AddError<C> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(self.description())
+    }
+}
+
+impl<C> Error for AddError<C> {
+    fn description(&self) -> &str {
+        match *self {
+            AddError::Broken(_) => "Connection was broken",
+            AddError::PoolFull(_) => "Pool was already at capacity",
+        }
+    }
+}
+
+/// A snapshot of a `Pool`'s state, as returned by `Pool::state`.
+#[derive(Debug, Clone, Copy)]
+pub struct State {
+    /// The number of connections currently managed by the pool.
+    ///
+    /// This may include connections that are still being opened.
+    pub connections: u32,
+    /// The number of idle connections currently sitting in the pool.
+    pub idle_connections: u32,
+}
+
 /// An error returned by `Pool::get` if it times out without retrieving a connection.
 #[derive(Debug)]
 pub struct GetTimeout;
@@ -165,7 +342,8 @@ impl<M> Pool<M> where M: ConnectionManager {
     /// Returns an `Err` value if `initialization_fail_fast` is set to true in
     /// the configuration and the pool is unable to open all of its
     /// connections.
-    pub fn new(config: Config,
+    pub fn new(config: Config<<M as ConnectionManager>::Connection,
+                               <M as ConnectionManager>::Error>,
                manager: M,
                error_handler: Box<ErrorHandler<<M as ConnectionManager>::Error>>)
                -> Result<Pool<M>, InitializationError> {
@@ -174,25 +352,31 @@ impl<M> Pool<M> where M: ConnectionManager {
             num_conns: 0,
         };
 
+        // Pull out what we need before `config` is moved into `SharedPool`.
+        let helper_threads = config.helper_threads() as usize;
+        let min_idle = config.min_idle().unwrap_or(config.max_size());
+
         let shared = Arc::new(SharedPool {
             config: config,
             manager: manager,
             error_handler: error_handler,
             internals: Mutex::new(internals),
             cond: Condvar::new(),
-            thread_pool: ScheduledThreadPool::new(config.helper_threads() as usize),
+            thread_pool: ScheduledThreadPool::new(helper_threads),
         });
 
-        for _ in 0..config.pool_size() {
+        for _ in 0..min_idle {
             add_connection(Duration::zero(), &shared);
         }
 
+        schedule_reaper(&shared);
+
         if shared.config.initialization_fail_fast() {
             let internals = shared.internals.lock().unwrap();
             let initialized = shared.cond.wait_timeout_with(internals,
                                                             shared.config.connection_timeout(),
                                                             |internals| {
-                internals.unwrap().num_conns == shared.config.pool_size()
+                internals.unwrap().num_conns == min_idle
             }).unwrap().1;
 
             if !initialized {
@@ -215,7 +399,7 @@ impl<M> Pool<M> where M: ConnectionManager {
 
         loop {
             match internals.conns.pop_front() {
-                Some(mut conn) => {
+                Some(IdleConn { mut conn, created_at, .. }) => {
                     drop(internals);
 
                     if self.shared.config.test_on_check_out() {
@@ -228,12 +412,32 @@ impl<M> Pool<M> where M: ConnectionManager {
                         }
                     }
 
+                    if let Some(customizer) = self.shared.config.connection_customizer() {
+                        if let Err(e) = customizer.on_acquire(&mut conn) {
+                            self.shared.error_handler.handle_error(e);
+                            internals = self.shared.internals.lock().unwrap();
+                            internals.num_conns -= 1;
+                            add_connection(Duration::zero(), &self.shared);
+                            continue
+                        }
+                    }
+
                     return Ok(PooledConnection {
                         pool: self,
                         conn: Some(conn),
+                        created_at: created_at,
                     })
                 }
                 None => {
+                    if internals.num_conns < self.shared.config.max_size() {
+                        // Reserve the slot here, under the same lock that
+                        // just checked capacity, so concurrent callers (and
+                        // this same caller on a later spurious wake) see the
+                        // updated count and never schedule past `max_size`.
+                        internals.num_conns += 1;
+                        add_reserved_connection(Duration::zero(), &self.shared);
+                    }
+
                     let now = SteadyTime::now();
                     let (new_internals, no_timeout) =
                         self.shared.cond.wait_timeout(internals, end - now).unwrap();
@@ -247,7 +451,54 @@ impl<M> Pool<M> where M: ConnectionManager {
         }
     }
 
-    fn put_back(&self, mut conn: <M as ConnectionManager>::Connection) {
+    /// Returns a snapshot of the pool's current state.
+    ///
+    /// This is cheap, acquiring the internal lock only long enough to copy
+    /// out the connection counts, and is intended for wiring pool
+    /// utilization into metrics or monitoring.
+    pub fn state(&self) -> State {
+        let internals = self.shared.internals.lock().unwrap();
+        State {
+            connections: internals.num_conns,
+            idle_connections: internals.conns.len() as u32,
+        }
+    }
+
+    /// Adds a connection created outside the pool to the pool.
+    ///
+    /// This is useful for warming the pool with a connection re-established
+    /// out-of-band, or for transferring a connection from one pool to
+    /// another. The connection is handed back inside an `AddError` if it is
+    /// broken or if the pool is already full, so it is never silently
+    /// dropped.
+    pub fn add(&self,
+               mut conn: <M as ConnectionManager>::Connection)
+               -> Result<(), AddError<<M as ConnectionManager>::Connection>> {
+        if self.shared.manager.has_broken(&mut conn) {
+            return Err(AddError::Broken(conn));
+        }
+
+        let mut internals = self.shared.internals.lock().unwrap();
+        if internals.num_conns >= self.shared.config.max_size() {
+            return Err(AddError::PoolFull(conn));
+        }
+
+        let now = SteadyTime::now();
+        internals.conns.push_back(IdleConn {
+            conn: conn,
+            created_at: now,
+            last_used_at: now,
+        });
+        internals.num_conns += 1;
+        self.shared.cond.notify_one();
+        Ok(())
+    }
+
+    fn put_back(&self, mut conn: <M as ConnectionManager>::Connection, created_at: SteadyTime) {
+        if let Some(customizer) = self.shared.config.connection_customizer() {
+            customizer.on_release(&mut conn);
+        }
+
         // This is specified to be fast, but call it before locking anyways
         let broken = self.shared.manager.has_broken(&mut conn);
 
@@ -255,7 +506,11 @@ impl<M> Pool<M> where M: ConnectionManager {
         if broken {
             internals.num_conns -= 1;
         } else {
-            internals.conns.push_back(conn);
+            internals.conns.push_back(IdleConn {
+                conn: conn,
+                created_at: created_at,
+                last_used_at: SteadyTime::now(),
+            });
             self.shared.cond.notify_one();
         }
     }
@@ -265,6 +520,7 @@ impl<M> Pool<M> where M: ConnectionManager {
 pub struct PooledConnection<'a, M> where M: ConnectionManager {
     pool: &'a Pool<M>,
     conn: Option<<M as ConnectionManager>::Connection>,
+    created_at: SteadyTime,
 }
 
 impl<'a, M> fmt::Debug for PooledConnection<'a, M>
@@ -279,7 +535,7 @@ impl<'a, M> fmt::Debug for PooledConnection<'a, M>
 #[unsafe_destructor]
 impl<'a, M> Drop for PooledConnection<'a, M> where M: ConnectionManager {
     fn drop(&mut self) {
-        self.pool.put_back(self.conn.take().unwrap());
+        self.pool.put_back(self.conn.take().unwrap(), self.created_at);
     }
 }
 
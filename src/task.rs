@@ -0,0 +1,144 @@
+//! An internal thread pool used to run maintenance jobs in the background.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex, Condvar};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::thread;
+use std::time::Duration;
+use time::SteadyTime;
+
+type Job = Box<FnMut() + Send>;
+
+struct ScheduledJob {
+    time: SteadyTime,
+    job: Job,
+}
+
+impl PartialEq for ScheduledJob {
+    fn eq(&self, other: &ScheduledJob) -> bool {
+        self.time == other.time
+    }
+}
+
+impl Eq for ScheduledJob {}
+
+impl PartialOrd for ScheduledJob {
+    fn partial_cmp(&self, other: &ScheduledJob) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ScheduledJob {
+    fn cmp(&self, other: &ScheduledJob) -> Ordering {
+        // `BinaryHeap` is a max-heap, so reverse the comparison to pop the
+        // earliest scheduled job first.
+        other.time.cmp(&self.time)
+    }
+}
+
+struct Shared {
+    queue: Mutex<BinaryHeap<ScheduledJob>>,
+    condvar: Condvar,
+    cleared: AtomicBool,
+}
+
+fn schedule(shared: &Arc<Shared>, delay: Duration, job: Job) {
+    let time = SteadyTime::now() + delay;
+    let mut queue = shared.queue.lock().unwrap();
+    queue.push(ScheduledJob { time: time, job: job });
+    shared.condvar.notify_all();
+}
+
+fn schedule_repeating<F>(shared: Arc<Shared>, delay: Duration, rate: Duration, mut job: F)
+    where F: FnMut() + Send + 'static
+{
+    let shared2 = shared.clone();
+    schedule(&shared, delay, Box::new(move || {
+        if shared2.cleared.load(AtomicOrdering::SeqCst) {
+            return;
+        }
+
+        job();
+        schedule_repeating(shared2.clone(), rate, rate, job);
+    }));
+}
+
+/// A pool of worker threads which run jobs immediately or after a delay.
+pub struct ScheduledThreadPool {
+    shared: Arc<Shared>,
+}
+
+impl ScheduledThreadPool {
+    /// Creates a new thread pool with the given number of worker threads.
+    pub fn new(num_threads: usize) -> ScheduledThreadPool {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(BinaryHeap::new()),
+            condvar: Condvar::new(),
+            cleared: AtomicBool::new(false),
+        });
+
+        for _ in 0..num_threads {
+            let shared = shared.clone();
+            thread::spawn(move || worker_loop(shared));
+        }
+
+        ScheduledThreadPool { shared: shared }
+    }
+
+    /// Schedules a job to run after the given delay.
+    pub fn run_after<F>(&self, delay: Duration, job: F) where F: FnOnce() + Send + 'static {
+        let mut job = Some(job);
+        schedule(&self.shared, delay, Box::new(move || {
+            if let Some(job) = job.take() {
+                job();
+            }
+        }));
+    }
+
+    /// Schedules a job to run repeatedly, first after `initial_delay` and
+    /// then `rate` after each run finishes.
+    ///
+    /// The job stops being rescheduled once `clear` is called.
+    pub fn run_at_fixed_rate<F>(&self, initial_delay: Duration, rate: Duration, job: F)
+        where F: FnMut() + Send + 'static
+    {
+        schedule_repeating(self.shared.clone(), initial_delay, rate, job);
+    }
+
+    /// Removes all jobs that have not yet started running, and prevents any
+    /// repeating job from being rescheduled.
+    ///
+    /// Jobs already in progress are left to finish.
+    pub fn clear(&self) {
+        self.shared.cleared.store(true, AtomicOrdering::SeqCst);
+        self.shared.queue.lock().unwrap().clear();
+    }
+}
+
+fn worker_loop(shared: Arc<Shared>) {
+    loop {
+        let mut queue = shared.queue.lock().unwrap();
+
+        let mut job = loop {
+            match queue.peek() {
+                Some(scheduled) => {
+                    let now = SteadyTime::now();
+                    if scheduled.time <= now {
+                        break queue.pop().unwrap().job;
+                    }
+
+                    let wait = (scheduled.time - now).num_milliseconds().max(0) as u64;
+                    let (new_queue, _) = shared.condvar.wait_timeout_ms(queue, wait).unwrap();
+                    queue = new_queue;
+                }
+                None => {
+                    queue = shared.condvar.wait(queue).unwrap();
+                }
+            }
+        };
+
+        drop(queue);
+        job();
+    }
+}